@@ -1,48 +1,583 @@
-use cpal::{default_host, Device, Stream, StreamConfig, InputCallbackInfo};
-use hound::{WavWriter, WavSpec, SampleFormat};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use chrono::Utc;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    default_host, Device, FromSample, InputCallbackInfo, Sample, StreamInstant,
+    SupportedStreamConfig,
+};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use hound::{WavSpec, WavWriter};
+use uuid::Uuid;
+
+/// Shared handle to the active WAV writer. The input callback locks it to append
+/// samples while the main thread can `take()` it to finalize cleanly on shutdown.
+/// On a rotation boundary the handle is swapped atomically for a fresh segment.
+type WavWriterHandle = Arc<Mutex<Option<WavWriter<BufWriter<File>>>>>;
+
+/// When to start a new segment file. A new file begins as soon as either the
+/// current one reaches `max_duration` of wall-clock time or `max_bytes` of
+/// written sample data, whichever comes first.
+struct RotationPolicy {
+    max_duration: Duration,
+    max_bytes: u64,
+}
+
+/// Bookkeeping for the segment currently being written.
+struct SegmentState {
+    start: Instant,
+    bytes: u64,
+    /// Capture instant of the first buffer written to this segment, relative to
+    /// the stream start — the reliable time base for the segment's samples.
+    capture_offset: Option<Duration>,
+}
+
+/// Path for a segment, named at the instant it starts:
+/// `<timestamp>_<uuid>_<seq>.wav`. The monotonic `seq` guarantees uniqueness even
+/// when two rotations fall in the same millisecond.
+fn segment_path(dir: &Path, session_id: &Uuid, seq: u64) -> PathBuf {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+    dir.join(format!("{stamp}_{session_id}_{seq:06}.wav"))
+}
+
+/// Background worker that keeps the real-time callback free of disk I/O. On each
+/// rotation signal it opens the next segment, swaps it into the writer handle
+/// under a brief lock, and finalizes the previous one — both blocking operations
+/// running here, never on the audio thread. The callback keeps writing into the
+/// outgoing segment until the swap lands, so nothing is lost. Exits when the
+/// signal channel is closed.
+fn rotation_worker(
+    rx: Receiver<()>,
+    writer: WavWriterHandle,
+    rotating: Arc<AtomicBool>,
+    seq: Arc<AtomicU64>,
+    dir: PathBuf,
+    spec: WavSpec,
+    session_id: Uuid,
+) {
+    for _ in rx {
+        let next = seq.fetch_add(1, Ordering::Relaxed);
+        match WavWriter::create(segment_path(&dir, &session_id, next), spec) {
+            Ok(new_writer) => {
+                // Recover the guard even if a writer panicked while holding the
+                // lock: the callback tolerates poisoning too, and a dead worker
+                // would silently stop all rotation.
+                let old = {
+                    let mut guard = writer.lock().unwrap_or_else(|e| e.into_inner());
+                    guard.replace(new_writer)
+                };
+                if let Some(old) = old {
+                    if let Err(err) = old.finalize() {
+                        eprintln!("Failed to finalize recording segment: {err}");
+                    }
+                }
+            }
+            Err(err) => eprintln!("Failed to open next recording segment: {err}"),
+        }
+        rotating.store(false, Ordering::Release);
+    }
+}
+
+/// A WAV writer that transparently rotates to a new timestamped file once the
+/// active segment exceeds the configured duration or size. Every file is named
+/// with a UTC timestamp plus a per-session UUID v4 so that concurrent recorders
+/// never collide, and the previous segment is finalized at each boundary so a
+/// kill between segments loses nothing already flushed. The file open/finalize
+/// disk I/O runs on a helper thread, never on the audio callback.
+struct RotatingRecorder {
+    spec: WavSpec,
+    dir: PathBuf,
+    session_id: Uuid,
+    policy: RotationPolicy,
+    writer: WavWriterHandle,
+    state: Mutex<SegmentState>,
+    /// Signals the worker to rotate; taken on shutdown to stop it.
+    rotate_tx: Mutex<Option<Sender<()>>>,
+    /// Set while a rotation is in flight, so repeated triggers don't pile up.
+    rotating: Arc<AtomicBool>,
+    /// Monotonic segment counter, disambiguating same-millisecond filenames.
+    seq: Arc<AtomicU64>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl RotatingRecorder {
+    /// Create a recorder, open its first segment, and spawn the rotation worker.
+    fn new(dir: PathBuf, spec: WavSpec, policy: RotationPolicy) -> Result<Arc<Self>, anyhow::Error> {
+        std::fs::create_dir_all(&dir)?;
+        let session_id = Uuid::new_v4();
+
+        // Opening the first segment here is a one-time startup cost, not on the
+        // callback.
+        let seq = Arc::new(AtomicU64::new(0));
+        let first = WavWriter::create(
+            segment_path(&dir, &session_id, seq.fetch_add(1, Ordering::Relaxed)),
+            spec,
+        )?;
+        let writer: WavWriterHandle = Arc::new(Mutex::new(Some(first)));
+        let rotating = Arc::new(AtomicBool::new(false));
+
+        let (rotate_tx, rotate_rx) = unbounded::<()>();
+        let worker = {
+            let writer = writer.clone();
+            let rotating = rotating.clone();
+            let seq = seq.clone();
+            let dir = dir.clone();
+            std::thread::spawn(move || {
+                rotation_worker(rotate_rx, writer, rotating, seq, dir, spec, session_id)
+            })
+        };
+
+        Ok(Arc::new(RotatingRecorder {
+            spec,
+            dir,
+            session_id,
+            policy,
+            writer,
+            state: Mutex::new(SegmentState {
+                start: Instant::now(),
+                bytes: 0,
+                capture_offset: None,
+            }),
+            rotate_tx: Mutex::new(Some(rotate_tx)),
+            rotating,
+            seq,
+            worker: Mutex::new(Some(worker)),
+        }))
+    }
+
+    /// Signal the worker to start a new segment. Does no disk I/O, so it is safe
+    /// to call from the audio callback. Rotations in flight coalesce: the segment
+    /// counters reset immediately and the actual file swap lands a moment later
+    /// on the worker, with the outgoing segment receiving the samples in between.
+    fn rotate(&self) {
+        // Coalesce: ignore further triggers until the worker finishes this one.
+        if self.rotating.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        {
+            let mut state = self.state.lock().unwrap();
+            state.start = Instant::now();
+            state.bytes = 0;
+            state.capture_offset = None;
+        }
+        if let Some(tx) = self.rotate_tx.lock().unwrap().as_ref() {
+            if tx.send(()).is_err() {
+                // Worker is gone; clear the flag so the recorder doesn't wedge.
+                self.rotating.store(false, Ordering::Release);
+            }
+        }
+    }
+
+    /// Record the capture time base of the current segment on its first buffer.
+    fn note_capture(&self, offset: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if state.capture_offset.is_none() {
+            state.capture_offset = Some(offset);
+        }
+    }
+
+    /// True once the active segment has hit either rotation trigger.
+    fn should_rotate(&self, state: &SegmentState) -> bool {
+        state.start.elapsed() >= self.policy.max_duration || state.bytes >= self.policy.max_bytes
+    }
+
+    /// Stop the worker (draining any queued rotation) and finalize whatever
+    /// segment is currently installed. Called on shutdown.
+    fn finalize(&self) -> Result<(), anyhow::Error> {
+        // Close the signal channel first so no rotation is left in flight; the
+        // worker drains queued signals, performs the final swap, and exits.
+        self.rotate_tx.lock().unwrap().take();
+        if let Some(handle) = self.worker.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            writer.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+/// An acoustic activity burst detected on the live input stream.
+#[derive(Debug, Clone)]
+struct Event {
+    /// When the burst was detected, relative to the process clock.
+    timestamp: Instant,
+    /// Capture time of the buffer, relative to the stream start. Derived from the
+    /// hardware timestamps so two-mic streams that started at slightly different
+    /// instants can be aligned before correlation.
+    capture_offset: Duration,
+    /// Largest absolute sample amplitude observed since the previous event.
+    peak_amplitude: f32,
+}
+
+/// Out-of-band stream health, propagated off the audio callback so the rotation
+/// and event subsystems can mark gaps in the recorded data.
+#[derive(Debug, Clone)]
+enum StreamStatus {
+    /// A discontinuity inferred from the capture timestamps: the gap between
+    /// consecutive buffers exceeded their nominal duration (overflow/overrun).
+    Gap { at: Duration, missing: Duration },
+    /// An error reported by the cpal stream itself.
+    Error(String),
+}
+
+/// Online energy-plus-onset detector run inside the input callback.
+///
+/// A fixed-capacity ring buffer holds the squared samples of a short window so a
+/// short-term mean-square envelope can be updated in O(1) per sample with no
+/// per-callback allocation. A slower exponential moving average tracks the
+/// long-term background energy. When the short-term energy exceeds the long-term
+/// average by `trigger_ratio`, an `Event` is emitted through a `crossbeam`
+/// channel, subject to a refractory period that suppresses double-triggering on
+/// the same burst.
+struct EventDetector {
+    ring: Vec<f64>,
+    pos: usize,
+    short_sumsq: f64,
+    long_avg: f64,
+    alpha: f64,
+    trigger_ratio: f64,
+    refractory: Duration,
+    last_event: Option<Instant>,
+    peak: f32,
+    sender: Sender<Event>,
+}
+
+impl EventDetector {
+    /// `window` is the short-term envelope length in samples; `alpha` is the
+    /// smoothing factor of the long-term average (smaller adapts more slowly).
+    fn new(
+        window: usize,
+        alpha: f64,
+        trigger_ratio: f64,
+        refractory: Duration,
+        sender: Sender<Event>,
+    ) -> Self {
+        EventDetector {
+            ring: vec![0.0; window.max(1)],
+            pos: 0,
+            short_sumsq: 0.0,
+            long_avg: 0.0,
+            alpha,
+            trigger_ratio,
+            refractory,
+            last_event: None,
+            peak: 0.0,
+            sender,
+        }
+    }
+
+    /// Downmix an interleaved input buffer to mono and feed it through the
+    /// detector. `now` stamps any events emitted for this buffer on the process
+    /// clock; `capture_offset` is the buffer's hardware capture time relative to
+    /// the stream start.
+    fn process<T>(&mut self, input: &[T], channels: u16, now: Instant, capture_offset: Duration)
+    where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        let ch = channels.max(1) as usize;
+        for frame in input.chunks(ch) {
+            let mut acc = 0.0f32;
+            for &s in frame {
+                acc += f32::from_sample(s);
+            }
+            let sample = acc / ch as f32;
+
+            // O(1) short-term mean-square update via the ring buffer.
+            let sq = (sample as f64) * (sample as f64);
+            self.short_sumsq += sq - self.ring[self.pos];
+            self.ring[self.pos] = sq;
+            self.pos = (self.pos + 1) % self.ring.len();
+            let short_energy = self.short_sumsq / self.ring.len() as f64;
+
+            // Long-term background energy.
+            self.long_avg = self.long_avg * (1.0 - self.alpha) + short_energy * self.alpha;
+
+            let amp = sample.abs();
+            if amp > self.peak {
+                self.peak = amp;
+            }
+
+            if self.long_avg > 0.0 && short_energy > self.trigger_ratio * self.long_avg {
+                let fire = match self.last_event {
+                    Some(t) => now.duration_since(t) >= self.refractory,
+                    None => true,
+                };
+                if fire {
+                    let _ = self.sender.send(Event {
+                        timestamp: now,
+                        capture_offset,
+                        peak_amplitude: self.peak,
+                    });
+                    self.last_event = Some(now);
+                    self.peak = 0.0;
+                }
+            }
+        }
+    }
+}
+
+/// Tracks the capture time base of the stream and detects discontinuities from
+/// the `InputCallbackInfo` timestamps. The first buffer's capture instant is the
+/// origin; every later buffer is reported relative to it, and a larger-than-
+/// expected gap between consecutive buffers is surfaced as a `StreamStatus::Gap`.
+struct StreamClock {
+    base: Option<StreamInstant>,
+    last_capture: Option<StreamInstant>,
+    last_frames: usize,
+    sample_rate: f64,
+    status: Sender<StreamStatus>,
+}
+
+impl StreamClock {
+    fn new(sample_rate: f64, status: Sender<StreamStatus>) -> Self {
+        StreamClock {
+            base: None,
+            last_capture: None,
+            last_frames: 0,
+            sample_rate,
+            status,
+        }
+    }
+
+    /// Record a buffer and return its capture offset relative to the stream start.
+    fn observe(&mut self, info: &InputCallbackInfo, frames: usize) -> Duration {
+        let capture = info.timestamp().capture;
+        let base = *self.base.get_or_insert(capture);
+        let offset = capture.duration_since(&base).unwrap_or(Duration::ZERO);
+
+        if let Some(prev) = self.last_capture {
+            let delta = capture.duration_since(&prev).unwrap_or(Duration::ZERO);
+            let expected = Duration::from_secs_f64(self.last_frames as f64 / self.sample_rate);
+            // Allow half a sample of slack before flagging a gap.
+            let slack = Duration::from_secs_f64(0.5 / self.sample_rate);
+            if delta > expected + slack {
+                let _ = self.status.send(StreamStatus::Gap {
+                    at: offset,
+                    missing: delta - expected,
+                });
+            }
+        }
+
+        self.last_capture = Some(capture);
+        self.last_frames = frames;
+        offset
+    }
+}
 
 fn main() -> Result<(), anyhow::Error> {
     // Get the default host
     let host = default_host();
 
     // Get the default input device
-    let device: Device = host.default_input_device().ok_or_else(|| anyhow!("No input device available"))?;
+    let device: Device = host
+        .default_input_device()
+        .ok_or_else(|| anyhow!("No input device available"))?;
 
-    // Get the default input stream configuration
-    let config: StreamConfig = device.default_input_config()?.into();
+    // Get the default input stream configuration, keeping the full supported
+    // config so we can match its sample format at runtime.
+    let config: SupportedStreamConfig = device.default_input_config()?;
 
-    // Create a WAV writer
-    let spec = WavSpec {
-        channels: config.channels,
-        sample_rate: config.sample_rate.0,
-        bits_per_sample: 16,
-        sample_format: SampleFormat::Int,
+    // Derive the WAV spec from the device config rather than hardcoding it.
+    let spec = wav_spec_from_config(&config);
+
+    // One file per hour, or per gigabyte, whichever comes first.
+    let policy = RotationPolicy {
+        max_duration: Duration::from_secs(60 * 60),
+        max_bytes: 1024 * 1024 * 1024,
     };
-    let mut writer = WavWriter::create("output.wav", spec)?;
+    let recorder = RotatingRecorder::new(PathBuf::from("recordings"), spec, policy)?;
+
+    // Online event detection over the downmixed live stream. A short-term
+    // envelope of ~20 ms triggers when it runs 4x above the background energy,
+    // with a quarter-second refractory period between events.
+    let channels = config.channels();
+    let sample_rate = config.sample_rate().0 as f64;
+    let window = (sample_rate * 0.02) as usize;
+    let (event_tx, event_rx) = unbounded::<Event>();
+    let mut detector = EventDetector::new(
+        window,
+        0.001,
+        4.0,
+        Duration::from_millis(250),
+        event_tx,
+    );
 
-    // Define the callback for handling audio data
-    let callback = move |data: &[f32], _: &InputCallbackInfo| {
-        for &sample in data {
-            writer.write_sample((sample * i16::MAX as f32) as i16)?;
+    // Out-of-band stream health: gaps inferred from timestamps and stream errors.
+    let (status_tx, status_rx) = unbounded::<StreamStatus>();
+    let mut clock = StreamClock::new(sample_rate, status_tx.clone());
+
+    // Log hive activity bursts as they arrive, off the audio callback thread.
+    let start = Instant::now();
+    let consumer = std::thread::spawn(move || {
+        for event in event_rx {
+            let offset = event.timestamp.duration_since(start);
+            println!(
+                "event @ {:.3}s (capture {:.3}s) peak={:.4}",
+                offset.as_secs_f64(),
+                event.capture_offset.as_secs_f64(),
+                event.peak_amplitude
+            );
         }
-        Ok(())
+    });
+
+    // Log stream status (gaps/errors) so downstream code can mark the data.
+    let status_consumer = std::thread::spawn(move || {
+        for status in status_rx {
+            match status {
+                StreamStatus::Gap { at, missing } => eprintln!(
+                    "stream gap at {:.3}s: missing {:.3}ms",
+                    at.as_secs_f64(),
+                    missing.as_secs_f64() * 1000.0
+                ),
+                StreamStatus::Error(err) => eprintln!("stream error: {err}"),
+            }
+        }
+    });
+
+    // The error callback reports cpal stream errors onto the same status channel.
+    let err_status = status_tx.clone();
+    let err_fn = move |err: cpal::StreamError| {
+        eprintln!("Stream error: {}", err);
+        let _ = err_status.send(StreamStatus::Error(err.to_string()));
     };
 
-    // Build the stream with the defined callback
-    let stream: Stream = device.build_input_stream(&config, callback, err_fn)?;
+    // Dispatch on the device's native sample format, converting each sample to the
+    // matching WAV output type, as the cpal recording example does.
+    let stream_recorder = recorder.clone();
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::I16 => device.build_input_stream(
+            &config.into(),
+            move |data, info: &_| write_input_data::<i16, i16>(data, info, &stream_recorder, &mut detector, &mut clock, channels),
+            err_fn,
+            None,
+        )?,
+        cpal::SampleFormat::U16 => device.build_input_stream(
+            &config.into(),
+            move |data, info: &_| write_input_data::<u16, i16>(data, info, &stream_recorder, &mut detector, &mut clock, channels),
+            err_fn,
+            None,
+        )?,
+        cpal::SampleFormat::I32 => device.build_input_stream(
+            &config.into(),
+            move |data, info: &_| write_input_data::<i32, i32>(data, info, &stream_recorder, &mut detector, &mut clock, channels),
+            err_fn,
+            None,
+        )?,
+        cpal::SampleFormat::F32 => device.build_input_stream(
+            &config.into(),
+            move |data, info: &_| write_input_data::<f32, f32>(data, info, &stream_recorder, &mut detector, &mut clock, channels),
+            err_fn,
+            None,
+        )?,
+        format => return Err(anyhow!("unsupported sample format '{format}'")),
+    };
 
     // Start the stream
     stream.play()?;
 
     // Keep the stream alive
     std::thread::sleep(std::time::Duration::from_secs(10));
+    drop(stream);
+
+    // Finalize the last segment so its header sizes are written out.
+    recorder.finalize()?;
+
+    // Dropping the stream dropped the detector and clock along with their senders;
+    // drop our remaining status handle so both consumer threads drain and exit.
+    drop(status_tx);
+    let _ = consumer.join();
+    let _ = status_consumer.join();
 
     Ok(())
 }
 
-// Error handling function for the audio stream
-fn err_fn(err: cpal::StreamError) {
-    eprintln!("Stream error: {}", err);
+/// Derive a `WavSpec` (channels, sample rate, bit depth, integer/float format)
+/// from the device's supported stream config, so the output matches whatever the
+/// hardware actually delivers.
+fn wav_spec_from_config(config: &SupportedStreamConfig) -> WavSpec {
+    WavSpec {
+        channels: config.channels(),
+        sample_rate: config.sample_rate().0,
+        bits_per_sample: (config.sample_format().sample_size() * 8) as u16,
+        sample_format: if config.sample_format().is_float() {
+            hound::SampleFormat::Float
+        } else {
+            hound::SampleFormat::Int
+        },
+    }
+}
+
+/// Append a buffer of input samples to the active segment, converting from the
+/// device sample type `T` to the WAV sample type `U`, and rotate to a new file
+/// once a trigger is hit. Generic over both types so the same code path serves
+/// every supported format.
+fn write_input_data<T, U>(
+    input: &[T],
+    info: &InputCallbackInfo,
+    recorder: &RotatingRecorder,
+    detector: &mut EventDetector,
+    clock: &mut StreamClock,
+    channels: u16,
+) where
+    T: Sample,
+    U: Sample + hound::Sample + FromSample<T>,
+    f32: FromSample<T>,
+{
+    // Establish the capture time base and flag any gap since the last buffer.
+    let frames = input.len() / (channels.max(1) as usize);
+    let capture_offset = clock.observe(info, frames);
+    recorder.note_capture(capture_offset);
+
+    // Run online detection before the data is consumed by the writer.
+    detector.process(input, channels, Instant::now(), capture_offset);
+
+    // Block on the lock rather than `try_lock`: dropping the whole buffer on
+    // contention would lose audio with no gap marker. The lock is only ever held
+    // briefly here (rotation's disk I/O runs on a helper thread), so this does
+    // not stall capture.
+    if let Ok(mut guard) = recorder.writer.lock() {
+        if let Some(writer) = guard.as_mut() {
+            for &sample in input.iter() {
+                let sample: U = U::from_sample(sample);
+                let _ = writer.write_sample(sample);
+            }
+        }
+    }
+
+    let bytes = (input.len() as u64) * (recorder.spec.bits_per_sample as u64 / 8);
+    let rotate = {
+        let mut state = recorder.state.lock().unwrap();
+        state.bytes += bytes;
+        recorder.should_rotate(&state)
+    };
+    if rotate {
+        recorder.rotate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The live-recording handles must stay Send + Sync so that past segments can
+    // be analyzed on a rayon thread pool while recording continues.
+    #[test]
+    fn handles_are_thread_safe() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<WavWriterHandle>();
+        assert_send_sync::<Sender<Event>>();
+        assert_send_sync::<Sender<StreamStatus>>();
+    }
 }
 
 /*